@@ -1,8 +1,7 @@
 use crate::util::*;
 use crate::errors::*;
-use bytes::{Buf, IntoBuf, Bytes};
-use std::io::{Cursor, self};
 use crate::dot11::info::*;
+use std::fmt;
 
 #[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum FrameType {
@@ -41,160 +40,288 @@ pub enum FrameSubType {
     UnHandled,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum ControlSubType {
+    BlockAckReq,
+    BlockAck,
+    RTS,
+    CTS,
+    ACK,
+    UnHandled,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum AckPolicy {
+    NormalAck,
+    NoAck,
+    NoExplicitAck,
+    BlockAck,
+}
+
+/// Decoded contents of the 2-byte QoS Control field carried by QoS data
+/// frames, so callers can classify priority traffic and spot A-MSDU
+/// aggregates without re-parsing the raw bytes themselves.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct QosControl {
+    pub tid: u8,
+    pub eosp: bool,
+    pub ack_policy: AckPolicy,
+    pub amsdu_present: bool,
+}
+
+impl QosControl {
+    pub fn from_bytes(input: &[u8]) -> QosControl {
+        let low = input[0];
+
+        QosControl {
+            tid: low & 0b0000_1111,
+            eosp: flag_is_set(low, 4),
+            ack_policy: match (low & 0b0110_0000) >> 5 {
+                0 => AckPolicy::NormalAck,
+                1 => AckPolicy::NoAck,
+                2 => AckPolicy::NoExplicitAck,
+                _ => AckPolicy::BlockAck,
+            },
+            amsdu_present: flag_is_set(low, 7),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Dot11Header {
     pub frame_control: FrameControl,
     pub duration: [u8; 2],
-    pub dst: String,
-    pub src: String,
-    pub bssid: String,
+    pub dst: [u8; 6],
+    pub src: [u8; 6],
+    pub bssid: [u8; 6],
     pub seq_ctl: [u8; 2],
+    pub qos_control: Option<QosControl>,
     pub info: BodyInformation,
 }
 
 impl Dot11Header {
     pub fn from_bytes(input: &[u8]) -> Result<Dot11Header> {
-        use std::io::Read;
-
-        let buf = Bytes::from(input).into_buf();
-        let mut reader = buf.reader();
+        if input.len() < 4 {
+            bail!("frame too short for FrameControl + Duration");
+        }
 
-        let mut control = [0; 2];
-        reader.read(&mut control)?;
-        let frame_control = FrameControl::from_bytes(&control)?;
+        let frame_control = FrameControl::from_bytes(&input[0..2])?;
 
         let mut duration = [0; 2];
-        reader.read(&mut duration)?;
+        duration.copy_from_slice(&input[2..4]);
 
-        let mut mac_addresses = [0; 18];
-        reader.read(&mut mac_addresses)?;
+        if frame_control.frame_type() == FrameType::Control {
+            return Dot11Header::control_from_bytes(frame_control, duration, &input[4..]);
+        }
 
-        let (dst, src, bssid) = Dot11Header::parse_address(frame_control, &mac_addresses);
+        // Addr1/Addr2/Addr3 + SeqCtl are always present on management/data
+        // frames, regardless of subtype.
+        if input.len() < 24 {
+            bail!("frame too short for Addr1/Addr2/Addr3 + SeqCtl");
+        }
+        let mac_addresses = &input[4..22];
 
         let mut seq_ctl = [0; 2];
-        reader.read(&mut seq_ctl)?;
+        seq_ctl.copy_from_slice(&input[22..24]);
 
-        let mut dst2 = vec![];
-        io::copy(&mut reader, &mut dst2)?;
+        let mut offset = 24;
 
-        let body_information = Dot11Header::parse_body(frame_control, &dst2[..]);
+        // Addr4 only shows up on WDS (AP-to-AP) frames, i.e. when both
+        // to_ds and from_ds are set.
+        let has_addr4 = frame_control.to_ds() && frame_control.from_ds();
+        let addr4 = if has_addr4 {
+            if input.len() < offset + 6 {
+                bail!("frame too short for Addr4");
+            }
+            let addr4 = &input[offset..offset + 6];
+            offset += 6;
+            addr4
+        } else {
+            &[][..]
+        };
+
+        let (dst, src, bssid) = Dot11Header::parse_address(frame_control, mac_addresses, addr4, has_addr4);
+
+        // QoS subtypes append a 2-byte QoS Control field after SeqCtl/Addr4.
+        let is_qos = matches!(
+            frame_control.frame_subtype(),
+            FrameSubType::QoS
+                | FrameSubType::QoSCfPull
+                | FrameSubType::QoSCfAckCfPull
+                | FrameSubType::QoSNullData
+        );
+        let qos_control = if is_qos {
+            if input.len() < offset + 2 {
+                bail!("frame too short for QoS Control");
+            }
+            let qos_control = QosControl::from_bytes(&input[offset..offset + 2]);
+            offset += 2;
+            Some(qos_control)
+        } else {
+            None
+        };
 
-        let header = Dot11Header {
+        // The `order` bit on a QoS data frame signals a trailing 4-byte
+        // HT Control field.
+        if is_qos && frame_control.order() {
+            if input.len() < offset + 4 {
+                bail!("frame too short for HT Control");
+            }
+            offset += 4;
+        }
+
+        let body_information = Dot11Header::parse_body(frame_control, &input[offset..]);
+
+        Ok(Dot11Header {
             frame_control,
             duration,
             dst,
             src,
             bssid,
             seq_ctl,
+            qos_control,
             info: body_information,
+        })
+    }
+
+    fn control_from_bytes(
+        frame_control: FrameControl,
+        duration: [u8; 2],
+        rest: &[u8],
+    ) -> Result<Dot11Header> {
+        // Control frames have a truncated header: FrameControl(2) +
+        // Duration(2) + RA(6) [+ TA(6) for RTS/BlockAckReq/BlockAck].
+        // There is no BSSID, sequence control, or body to speak of.
+        if rest.len() < 6 {
+            bail!("frame too short for a Control frame receiver address");
+        }
+        let dst = MACField::from_slice(&rest[0..6]).0;
+
+        let src = match frame_control.control_subtype() {
+            Some(ControlSubType::RTS) | Some(ControlSubType::BlockAckReq) | Some(ControlSubType::BlockAck) => {
+                if rest.len() < 12 {
+                    bail!("frame too short for a transmitter address");
+                }
+                MACField::from_slice(&rest[6..12]).0
+            }
+            _ => [0; 6],
         };
-        Ok(header)
+
+        let control_subtype = frame_control.control_subtype().unwrap_or(ControlSubType::UnHandled);
+
+        Ok(Dot11Header {
+            frame_control,
+            duration,
+            dst,
+            src,
+            bssid: [0; 6],
+            seq_ctl: [0; 2],
+            qos_control: None,
+            info: BodyInformation::Control(control_subtype),
+        })
     }
 
-    fn parse_address(frame_control: FrameControl, input: &[u8]) -> (String, String, String) {
-        let mut dst = String::from("");
-        let mut src = String::from("");
-        let mut bssid = String::from("");
-
-        let addresses = FrameAddresses::from_bytes(input).unwrap();
-
-        if frame_control.to_ds && frame_control.from_ds {
-            dst.push_str(&addresses.addr3.addr);
-            src.push_str(&addresses.addr4.addr);
-        } else if frame_control.to_ds {
-            dst.push_str(&addresses.addr2.addr);
-            src.push_str(&addresses.addr3.addr);
-            bssid.push_str(&addresses.addr1.addr);
-        } else if frame_control.from_ds {
-            dst.push_str(&addresses.addr3.addr);
-            src.push_str(&addresses.addr1.addr);
-            bssid.push_str(&addresses.addr2.addr);
+    fn parse_address(
+        frame_control: FrameControl,
+        addrs: &[u8],
+        addr4: &[u8],
+        has_addr4: bool,
+    ) -> ([u8; 6], [u8; 6], [u8; 6]) {
+        let mut dst = [0; 6];
+        let mut src = [0; 6];
+        let mut bssid = [0; 6];
+
+        let addresses = FrameAddresses::from_bytes(addrs, addr4, has_addr4);
+
+        if frame_control.to_ds() && frame_control.from_ds() {
+            dst = addresses.addr3.0;
+            if let Some(addr4) = addresses.addr4 {
+                src = addr4.0;
+            }
+        } else if frame_control.to_ds() {
+            dst = addresses.addr2.0;
+            src = addresses.addr3.0;
+            bssid = addresses.addr1.0;
+        } else if frame_control.from_ds() {
+            dst = addresses.addr3.0;
+            src = addresses.addr1.0;
+            bssid = addresses.addr2.0;
         } else {
-            dst.push_str(&addresses.addr1.addr);
-            src.push_str(&addresses.addr2.addr);
-            bssid.push_str(&addresses.addr3.addr);
+            dst = addresses.addr1.0;
+            src = addresses.addr2.0;
+            bssid = addresses.addr3.0;
         }
 
         (dst, src, bssid)
     }
 
     fn parse_body(frame_control: FrameControl, input: &[u8]) -> BodyInformation {
-        match frame_control.frame_type {
-            FrameType::Management => {
-                if frame_control.frame_subtype == FrameSubType::Beacon {
-                    BodyInformation::Beacon(Beacon::from_bytes(input))
-                } else if frame_control.frame_subtype == FrameSubType::ProbeReq {
-                    BodyInformation::ProbeRequest(ProbeRequest::from_bytes(input))
-                } else if frame_control.frame_subtype == FrameSubType::ProbeResp {
-                    BodyInformation::ProbeResponse(ProbeResponse::from_bytes(input))
-                } else if frame_control.frame_subtype == FrameSubType::AssoReq {
+        match frame_control.frame_type() {
+            FrameType::Management => match frame_control.frame_subtype() {
+                FrameSubType::Beacon => BodyInformation::Beacon(Beacon::from_bytes(input)),
+                FrameSubType::ProbeReq => BodyInformation::ProbeRequest(ProbeRequest::from_bytes(input)),
+                FrameSubType::ProbeResp => BodyInformation::ProbeResponse(ProbeResponse::from_bytes(input)),
+                FrameSubType::AssoReq => {
                     BodyInformation::AssociationRequest(AssociationRequest::from_bytes(input))
-                } else if frame_control.frame_subtype == FrameSubType::AssoResp {
+                }
+                FrameSubType::AssoResp => {
                     BodyInformation::AssociationResponse(AssociationResponse::from_bytes(input))
-                } else {
-                    BodyInformation::UnHandled(true)
                 }
-            }
+                FrameSubType::Auth => BodyInformation::Authentication(Authentication::from_bytes(input)),
+                FrameSubType::Deauth => {
+                    BodyInformation::Deauthentication(Deauthentication::from_bytes(input))
+                }
+                FrameSubType::Disasso => {
+                    BodyInformation::Disassociation(Disassociation::from_bytes(input))
+                }
+                FrameSubType::ReassoReq => {
+                    BodyInformation::ReassociationRequest(ReassociationRequest::from_bytes(input))
+                }
+                FrameSubType::ReassoResp => {
+                    BodyInformation::ReassociationResponse(ReassociationResponse::from_bytes(input))
+                }
+                _ => BodyInformation::UnHandled(true),
+            },
             _ => BodyInformation::UnHandled(true),
         }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct FrameControl {
-    pub frame_type: FrameType,
-    pub frame_subtype: FrameSubType,
-    pub to_ds: bool,
-    pub from_ds: bool,
-    pub more_flag: bool,
-    pub retry: bool,
-    pub pwr_mgmt: bool,
-    pub more_data: bool,
-    pub wep: bool,
-    pub order: bool,
-}
+/// Thin wrapper over the raw 16-bit Frame Control word. Decoding happens
+/// on demand through the accessor methods below rather than up front,
+/// so parsing a header stores only the 16-bit word and allocates nothing.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct FrameControl(u16);
 
 impl FrameControl {
     pub fn from_bytes(input: &[u8]) -> Result<FrameControl> {
-        let mut cursor = Cursor::new(input);
-        let version_type_subtype = cursor.get_u8();
-        let flags = cursor.get_u8();
+        if input.len() < 2 {
+            bail!("FrameControl requires 2 bytes");
+        }
+
+        let fc = FrameControl(u16::from_le_bytes([input[0], input[1]]));
 
-        if FrameControl::protocol_version(version_type_subtype) != 0 {
+        if fc.protocol_version() != 0 {
             bail!("Unknow protocol version");
         }
 
-        let frame_type = FrameControl::frame_type(version_type_subtype);
-
-        let frame_subtype = match frame_type {
-            FrameType::Management => FrameControl::frame_subtype(version_type_subtype),
-            FrameType::Data => FrameControl::data_frame_subtype(version_type_subtype),
-            FrameType::Control => FrameControl::frame_subtype(version_type_subtype),
-            FrameType::Unknown => FrameControl::frame_subtype(version_type_subtype),
-        };
+        Ok(fc)
+    }
 
-        let fc = FrameControl {
-            frame_type,
-            frame_subtype,
-            to_ds: flag_is_set(flags, 0),
-            from_ds: flag_is_set(flags, 1),
-            more_flag: flag_is_set(flags, 2),
-            retry: flag_is_set(flags, 3),
-            pwr_mgmt: flag_is_set(flags, 4),
-            more_data: flag_is_set(flags, 5),
-            wep: flag_is_set(flags, 6),
-            order: flag_is_set(flags, 7),
-        };
+    fn version_type_subtype(self) -> u8 {
+        (self.0 & 0x00ff) as u8
+    }
 
-        Ok(fc)
+    fn flags(self) -> u8 {
+        (self.0 >> 8) as u8
     }
 
-    fn protocol_version(packet: u8) -> u8 {
-        packet & 0b0000_0011
+    pub fn protocol_version(self) -> u8 {
+        self.version_type_subtype() & 0b0000_0011
     }
 
-    fn frame_type(packet: u8) -> FrameType {
-        match (packet & 0b0000_1100) >> 2 {
+    pub fn frame_type(self) -> FrameType {
+        match (self.version_type_subtype() & 0b0000_1100) >> 2 {
             0 => FrameType::Management,
             1 => FrameType::Control,
             2 => FrameType::Data,
@@ -202,8 +329,31 @@ impl FrameControl {
         }
     }
 
-    fn frame_subtype(packet: u8) -> FrameSubType {
-        match (packet & 0b1111_0000) >> 4 {
+    pub fn frame_subtype(self) -> FrameSubType {
+        match self.frame_type() {
+            FrameType::Data => self.data_frame_subtype(),
+            FrameType::Control => FrameSubType::UnHandled,
+            _ => self.management_frame_subtype(),
+        }
+    }
+
+    pub fn control_subtype(self) -> Option<ControlSubType> {
+        if self.frame_type() != FrameType::Control {
+            return None;
+        }
+
+        Some(match (self.version_type_subtype() & 0b1111_0000) >> 4 {
+            0b1000 => ControlSubType::BlockAckReq,
+            0b1001 => ControlSubType::BlockAck,
+            0b1011 => ControlSubType::RTS,
+            0b1100 => ControlSubType::CTS,
+            0b1101 => ControlSubType::ACK,
+            _ => ControlSubType::UnHandled,
+        })
+    }
+
+    fn management_frame_subtype(self) -> FrameSubType {
+        match (self.version_type_subtype() & 0b1111_0000) >> 4 {
             0 => FrameSubType::AssoReq,
             1 => FrameSubType::AssoResp,
             2 => FrameSubType::ReassoReq,
@@ -219,8 +369,8 @@ impl FrameControl {
         }
     }
 
-    fn data_frame_subtype(packet: u8) -> FrameSubType {
-        match (packet & 0b1111_0000) >> 4 {
+    fn data_frame_subtype(self) -> FrameSubType {
+        match (self.version_type_subtype() & 0b1111_0000) >> 4 {
             0 => FrameSubType::Data,
             1 => FrameSubType::DataCfAck,
             2 => FrameSubType::DataCfPull,
@@ -237,6 +387,38 @@ impl FrameControl {
             _ => FrameSubType::UnHandled,
         }
     }
+
+    pub fn to_ds(self) -> bool {
+        flag_is_set(self.flags(), 0)
+    }
+
+    pub fn from_ds(self) -> bool {
+        flag_is_set(self.flags(), 1)
+    }
+
+    pub fn more_flag(self) -> bool {
+        flag_is_set(self.flags(), 2)
+    }
+
+    pub fn retry(self) -> bool {
+        flag_is_set(self.flags(), 3)
+    }
+
+    pub fn pwr_mgmt(self) -> bool {
+        flag_is_set(self.flags(), 4)
+    }
+
+    pub fn more_data(self) -> bool {
+        flag_is_set(self.flags(), 5)
+    }
+
+    pub fn protected(self) -> bool {
+        flag_is_set(self.flags(), 6)
+    }
+
+    pub fn order(self) -> bool {
+        flag_is_set(self.flags(), 7)
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -244,56 +426,50 @@ pub struct FrameAddresses {
     pub addr1: MACField,
     pub addr2: MACField,
     pub addr3: MACField,
-    pub addr4: MACField,
+    pub addr4: Option<MACField>,
 }
 
 impl FrameAddresses {
-    pub fn from_bytes(s: &[u8]) -> Result<FrameAddresses> {
-        use std::io::Read;
-
-        let buf = Bytes::from(s).into_buf();
-        let mut reader = buf.reader();
-
-        let mut mac_addr1 = [0; 6];
-        reader.read(&mut mac_addr1)?;
-        let addr1 = MACField::from_slice(&mac_addr1);
-
-        let mut mac_addr2 = [0; 6];
-        reader.read(&mut mac_addr2)?;
-        let addr2 = MACField::from_slice(&mac_addr2);
-
-        let mut mac_addr3 = [0; 6];
-        reader.read(&mut mac_addr3)?;
-        let addr3 = MACField::from_slice(&mac_addr3);
-
-        let mut seq_ctl = [0; 2];
-        reader.read(&mut seq_ctl)?;
-
-        let mut mac_addr4 = [0; 6];
-        reader.read(&mut mac_addr4)?;
-        let addr4 = MACField::from_slice(&mac_addr4);
+    /// `addrs` is the fixed 18-byte Addr1/Addr2/Addr3 block. `addr4` is only
+    /// read when `has_addr4` is set (to_ds && from_ds), matching the WDS
+    /// frame layout where Addr4 trails the sequence control field.
+    pub fn from_bytes(addrs: &[u8], addr4: &[u8], has_addr4: bool) -> FrameAddresses {
+        let addr1 = MACField::from_slice(&addrs[0..6]);
+        let addr2 = MACField::from_slice(&addrs[6..12]);
+        let addr3 = MACField::from_slice(&addrs[12..18]);
+
+        let addr4 = if has_addr4 {
+            Some(MACField::from_slice(addr4))
+        } else {
+            None
+        };
 
-        Ok(FrameAddresses {
+        FrameAddresses {
             addr1,
             addr2,
             addr3,
             addr4,
-        })
+        }
     }
 }
 
-#[derive(Clone, Debug)]
-pub struct MACField {
-    pub addr: String,
-}
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct MACField(pub [u8; 6]);
 
 impl MACField {
     pub fn from_slice(s: &[u8]) -> MACField {
-        let addr = format!(
-            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
-            s[0], s[1], s[2], s[3], s[4], s[5]
-        );
+        let mut addr = [0u8; 6];
+        addr.copy_from_slice(&s[0..6]);
+        MACField(addr)
+    }
+}
 
-        MACField { addr }
+impl fmt::Display for MACField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            self.0[0], self.0[1], self.0[2], self.0[3], self.0[4], self.0[5]
+        )
     }
 }